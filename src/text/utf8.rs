@@ -1,45 +1,127 @@
-use std::io::{Error, ErrorKind, Result};
-
-#[derive(Clone, Copy)]
-enum State {
-    Valid,
-    Invalid,
-    AwaitingOneByte,
-    AwaitingTwoBytesA,
-    AwaitingTwoBytesB,
-    AwaitingTwoBytesC,
-    AwaitingThreeBytesA,
-    AwaitingThreeBytesB,
-    AwaitingThreeBytesC,
+use std::fmt;
+
+// See http://bjoern.hoehrmann.de/utf-8/decoder/dfa/
+//
+// The first 256 entries map a byte to a character class; the class value
+// doubles as the number of high bits to mask off a lead byte (via
+// `0xff >> class`) to recover its payload bits. The remaining entries are a transition table indexed by
+// `state + class`, where `state` is already the class-table-sized offset
+// (a multiple of 12) of the row for the state the DFA was in, so walking
+// the table never needs a multiply.
+#[rustfmt::skip]
+static UTF8D: [u8; 364] = [
+    0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0, 0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,
+    0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0, 0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,
+    0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0, 0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,
+    0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0, 0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,
+    1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1, 9,9,9,9,9,9,9,9,9,9,9,9,9,9,9,9,
+    7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7, 7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,
+    8,8,2,2,2,2,2,2,2,2,2,2,2,2,2,2, 2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,
+    10,3,3,3,3,3,3,3,3,3,3,3,3,4,3,3, 11,6,6,6,5,8,8,8,8,8,8,8,8,8,8,8,
+
+    0,12,24,36,60,96,84,12,12,12,48,72, 12,12,12,12,12,12,12,12,12,12,12,12,
+    12,0,12,12,12,12,12,0,12,0,12,12, 12,24,12,12,12,12,12,24,12,24,12,12,
+    12,12,12,12,12,12,12,24,12,12,12,12, 12,24,12,12,12,12,12,12,12,24,12,12,
+    12,12,12,12,12,12,12,36,12,36,12,12, 12,36,12,12,12,12,12,36,12,36,12,12,
+    12,36,12,12,12,12,12,12,12,12,12,12,
+];
+
+const UTF8_ACCEPT: u8 = 0;
+const UTF8_REJECT: u8 = 12;
+
+/// A DFA state, encoded as its row offset into the transition half of
+/// `UTF8D` so that `UTF8D[256 + state.0 + class]` is the next state.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct State(u8);
+
+impl State {
+    const VALID: State = State(UTF8_ACCEPT);
+    const INVALID: State = State(UTF8_REJECT);
+}
+
+/// Describes why a byte sequence is not valid UTF-8, with enough detail to
+/// locate the problem without re-scanning the input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// An ill-formed subsequence was found partway through the input.
+    Invalid {
+        /// Number of leading bytes that form valid UTF-8.
+        valid_prefix_len: usize,
+        /// Number of bytes making up the maximal ill-formed subsequence, by
+        /// the same "substitution of maximal subparts" rule `Utf8LossyDecoder`
+        /// follows: a byte that could itself legally begin a new sequence is
+        /// never counted as part of the one it rejected.
+        invalid_len: usize,
+    },
+    /// The input ended in the middle of an otherwise valid sequence.
+    Incomplete {
+        /// Number of leading bytes that form valid UTF-8.
+        valid_prefix_len: usize,
+    },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Invalid {
+                valid_prefix_len,
+                invalid_len,
+            } => write!(
+                f,
+                "invalid UTF-8 sequence of {} bytes at position {}",
+                invalid_len, valid_prefix_len
+            ),
+            DecodeError::Incomplete { valid_prefix_len } => write!(
+                f,
+                "incomplete UTF-8 sequence at position {}",
+                valid_prefix_len
+            ),
+        }
+    }
 }
 
+impl std::error::Error for DecodeError {}
+
 /// Streaming validator for UTF-8 text.
 pub struct Utf8Validator {
     processed_count: usize,
+    valid_up_to: usize,
     state: State,
+    // Scalar value accumulated so far for the sequence in progress; only
+    // meaningful once `state` has returned to `State::VALID`, at which
+    // point `process_byte` hands back the completed `char`.
+    cp: u32,
 }
 
 impl Utf8Validator {
     pub fn new() -> Self {
         Utf8Validator {
             processed_count: 0,
-            state: State::Valid,
+            valid_up_to: 0,
+            state: State::VALID,
+            cp: 0,
         }
     }
 
     /// Check that bytes are valid UTF-8.
-    /// Returns an io::Error with kind set to InvalidData otherwise.
-    pub fn validate(&mut self, bytes: &[u8]) -> Result<()> {
-        // Fast path for ASCII text
-        if let State::Valid = self.state {
-            if Self::is_ascii(bytes) {
-                self.processed_count += bytes.len();
+    /// Returns a `DecodeError` describing the ill-formed subsequence otherwise.
+    pub fn validate(&mut self, bytes: &[u8]) -> Result<(), DecodeError> {
+        let mut pos = 0;
+
+        // Fast path: skip a leading run of ASCII a word at a time. This is
+        // only safe to enter between sequences, so it's skipped entirely
+        // once a multi-byte sequence is in progress.
+        if let State::VALID = self.state {
+            pos = Self::ascii_prefix_len(bytes);
+            self.processed_count += pos;
+            self.valid_up_to = self.processed_count;
+            if pos == bytes.len() {
                 return Ok(());
             }
         }
 
         // Slow path for non-ASCII
-        for b in bytes.iter() {
+        for b in bytes[pos..].iter() {
             self.process_byte(b)?;
             self.processed_count += 1;
         }
@@ -48,47 +130,381 @@ impl Utf8Validator {
 
     /// Check that the bytestream ends in a valid state.
     /// Call this when there are no more bytes to process.
-    pub fn validate_end(&self) -> Result<()> {
+    pub fn validate_end(&self) -> Result<(), DecodeError> {
         match self.state {
-            State::Valid => Ok(()),
-            _ => {
-                let msg = format!("Expected continuation byte at end of stream");
-                Err(Error::new(ErrorKind::InvalidData, msg))
-            }
-        }
-    }
-
-    fn is_ascii(bytes: &[u8]) -> bool {
-        bytes.iter().all(|b| (b >> 7) == 0)
-    }
-
-    fn process_byte(&mut self, b: &u8) -> Result<()> {
-        // See http://bjoern.hoehrmann.de/utf-8/decoder/dfa/
-        self.state = match (self.state, b) {
-            (State::Valid, 0x00..=0x7f) => State::Valid,
-            (State::Valid, 0xc2..=0xdf) => State::AwaitingOneByte,
-            (State::Valid, 0xe1..=0xec) | (State::Valid, 0xee..=0xef) => State::AwaitingTwoBytesA,
-            (State::Valid, 0xe0) => State::AwaitingTwoBytesB,
-            (State::Valid, 0xed) => State::AwaitingTwoBytesC,
-            (State::Valid, 0xf0) => State::AwaitingThreeBytesA,
-            (State::Valid, 0xf1..=0xf3) => State::AwaitingThreeBytesB,
-            (State::Valid, 0xf4) => State::AwaitingThreeBytesC,
-            (State::AwaitingOneByte, 0x80..=0xbf) => State::Valid,
-            (State::AwaitingTwoBytesA, 0x80..=0xbf)
-            | (State::AwaitingTwoBytesB, 0xa0..=0xbf)
-            | (State::AwaitingTwoBytesC, 0x80..=0x9f) => State::AwaitingOneByte,
-            (State::AwaitingThreeBytesA, 0x90..=0xbf)
-            | (State::AwaitingThreeBytesB, 0x80..=0xbf)
-            | (State::AwaitingThreeBytesC, 0x80..=0xbf) => State::AwaitingTwoBytesA,
-            _ => State::Invalid,
-        };
+            State::VALID => Ok(()),
+            _ => Err(DecodeError::Incomplete {
+                valid_prefix_len: self.valid_up_to,
+            }),
+        }
+    }
+
+    /// Return the length of the leading run of ASCII bytes in `bytes`,
+    /// checking a whole `usize` word at a time rather than one byte at a
+    /// time. This is the hot loop when loading large, predominantly-ASCII
+    /// files, so unaligned head and tail bytes are checked individually but
+    /// the aligned middle is cleared 8 (or 4, on 32-bit targets) bytes per
+    /// iteration.
+    fn ascii_prefix_len(bytes: &[u8]) -> usize {
+        const WORD_SIZE: usize = std::mem::size_of::<usize>();
+        const HIGH_BITS: usize = usize::from_ne_bytes([0x80; WORD_SIZE]);
+
+        let mut pos = 0;
+
+        // Unaligned head, byte-by-byte.
+        while pos < bytes.len() && (bytes.as_ptr() as usize + pos) % WORD_SIZE != 0 {
+            if bytes[pos] & 0x80 != 0 {
+                return pos;
+            }
+            pos += 1;
+        }
+
+        // Aligned middle, a word at a time.
+        while pos + WORD_SIZE <= bytes.len() {
+            // Safety: `pos` was advanced above until aligned to `WORD_SIZE`,
+            // and the slice has at least `WORD_SIZE` bytes left from `pos`.
+            let word = unsafe { *(bytes.as_ptr().add(pos) as *const usize) };
+            if word & HIGH_BITS != 0 {
+                break;
+            }
+            pos += WORD_SIZE;
+        }
+
+        // Unaligned tail, byte-by-byte.
+        while pos < bytes.len() && bytes[pos] & 0x80 == 0 {
+            pos += 1;
+        }
+
+        pos
+    }
+
+    /// Feed one byte through the DFA. Returns the `char` it completed, if
+    /// any, which callers that only need validation (like `validate`) are
+    /// free to discard.
+    fn process_byte(&mut self, b: &u8) -> Result<Option<char>, DecodeError> {
+        let prev_state = self.state;
+        self.state = decode(self.state, &mut self.cp, *b);
 
         match self.state {
-            State::Invalid => {
-                let msg = format!("Invalid byte at position {}", self.processed_count);
-                Err(Error::new(ErrorKind::InvalidData, msg))
+            State::INVALID => Err(DecodeError::Invalid {
+                valid_prefix_len: self.valid_up_to,
+                // The DFA always consumes the rejecting byte before
+                // signalling `INVALID`, but per the maximal-subparts rule
+                // that byte only belongs to the ill-formed subsequence if
+                // it couldn't have legally started a new one itself, i.e.
+                // if it was rejected directly from `VALID` with no sequence
+                // in progress (`prev_state` was `VALID`).
+                invalid_len: if prev_state == State::VALID {
+                    1
+                } else {
+                    self.processed_count - self.valid_up_to
+                },
+            }),
+            State::VALID => {
+                self.valid_up_to = self.processed_count + 1;
+                // Safety: the DFA only returns to `VALID` once `cp` holds a
+                // complete scalar value; `UTF8D` excludes the transitions
+                // (overlong forms, surrogates, out-of-range lead bytes)
+                // that would let it hold anything else.
+                Ok(Some(unsafe { char::from_u32_unchecked(self.cp) }))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Feed one byte through the DFA from `state`, accumulating its payload
+/// bits into `cp`. `cp` only holds a complete scalar value once the
+/// returned state is `State::VALID`.
+fn decode(state: State, cp: &mut u32, b: u8) -> State {
+    let class = UTF8D[b as usize];
+    *cp = if state == State::VALID {
+        (0xffu32 >> class) & u32::from(b)
+    } else {
+        (u32::from(b) & 0x3f) | (*cp << 6)
+    };
+    State(UTF8D[256 + state.0 as usize + class as usize])
+}
+
+/// `decode` for callers that only care about the resulting state, not the
+/// scalar value it's assembling.
+fn transition(state: State, b: u8) -> State {
+    let mut cp = 0;
+    decode(state, &mut cp, b)
+}
+
+/// Streaming decoder that substitutes U+FFFD (the Unicode replacement
+/// character) for ill-formed byte sequences instead of failing.
+///
+/// Follows the Unicode "substitution of maximal subparts" rule: each
+/// ill-formed subsequence is replaced by exactly one U+FFFD, and decoding
+/// resumes at the byte that caused the rejection whenever that byte could
+/// legally begin a new sequence on its own.
+pub struct Utf8LossyDecoder {
+    state: State,
+    pending: Vec<u8>,
+}
+
+impl Utf8LossyDecoder {
+    pub fn new() -> Self {
+        Utf8LossyDecoder {
+            state: State::VALID,
+            pending: Vec::with_capacity(3),
+        }
+    }
+
+    /// Decode `bytes`, appending the result to `output`.
+    ///
+    /// State carries over between calls, so a multi-byte sequence split
+    /// across chunk boundaries still decodes correctly. Call `finish` once
+    /// there are no more bytes to flush a trailing incomplete sequence.
+    pub fn decode(&mut self, bytes: &[u8], output: &mut String) {
+        let mut pos = 0;
+        while pos < bytes.len() {
+            // Fast path for a run of ASCII bytes.
+            if let State::VALID = self.state {
+                let run_len = bytes[pos..]
+                    .iter()
+                    .take_while(|b| (**b >> 7) == 0)
+                    .count();
+                if run_len > 0 {
+                    // Safety: a run of bytes with their high bit unset is
+                    // always valid ASCII, hence valid UTF-8.
+                    output.push_str(unsafe {
+                        std::str::from_utf8_unchecked(&bytes[pos..pos + run_len])
+                    });
+                    pos += run_len;
+                    continue;
+                }
+            }
+
+            let b = bytes[pos];
+            self.state = transition(self.state, b);
+            match self.state {
+                State::INVALID => {
+                    output.push('\u{fffd}');
+                    if self.pending.is_empty() {
+                        // `b` can't legally begin a sequence either, so it
+                        // is itself the ill-formed subsequence.
+                        self.state = State::VALID;
+                        pos += 1;
+                    } else {
+                        // `b` doesn't belong to the ill-formed subsequence
+                        // buffered in `pending`, so leave it to be
+                        // reprocessed as the start of a new sequence.
+                        self.pending.clear();
+                        self.state = State::VALID;
+                    }
+                }
+                State::VALID => {
+                    self.pending.push(b);
+                    // Safety: `state` just returned to Valid, so `pending`
+                    // holds a complete, well-formed encoding of one scalar
+                    // value.
+                    output.push_str(unsafe { std::str::from_utf8_unchecked(&self.pending) });
+                    self.pending.clear();
+                    pos += 1;
+                }
+                _ => {
+                    self.pending.push(b);
+                    pos += 1;
+                }
             }
-            _ => Ok(()),
+        }
+    }
+
+    /// Flush a trailing incomplete sequence, if any, as a single U+FFFD.
+    /// Call this once there are no more bytes to decode.
+    pub fn finish(&mut self, output: &mut String) {
+        if !self.pending.is_empty() {
+            output.push('\u{fffd}');
+            self.pending.clear();
+            self.state = State::VALID;
+        }
+    }
+}
+
+/// Incremental, allocation-free decoder from bytes to `char`s.
+///
+/// Unlike `Utf8LossyDecoder`, this doesn't copy valid text into an output
+/// `String`: each decoded run is a `&str` borrowed directly out of the input
+/// slice passed to `next_chunk`/`last_chunk`. The exception is a sequence
+/// split across chunk boundaries: its bytes are copied into `incomplete`,
+/// completed on the next call, and returned from that buffer.
+pub struct Utf8Decoder {
+    incomplete: [u8; 4],
+    incomplete_len: usize,
+}
+
+impl Utf8Decoder {
+    pub fn new() -> Self {
+        Utf8Decoder {
+            incomplete: [0; 4],
+            incomplete_len: 0,
+        }
+    }
+
+    /// Decode the next chunk of bytes. Bytes trailing a still-incomplete
+    /// sequence are buffered and completed by a later call to `next_chunk`
+    /// or `last_chunk`.
+    pub fn next_chunk<'d, 'b>(&'d mut self, bytes: &'b [u8]) -> Utf8Chunks<'d, 'b> {
+        Utf8Chunks {
+            decoder: self,
+            bytes,
+            pos: 0,
+            last: false,
+        }
+    }
+
+    /// Decode the final chunk of bytes. Unlike `next_chunk`, a sequence left
+    /// incomplete at the end of `bytes` is reported as an error instead of
+    /// buffered.
+    pub fn last_chunk<'d, 'b>(&'d mut self, bytes: &'b [u8]) -> Utf8Chunks<'d, 'b> {
+        Utf8Chunks {
+            decoder: self,
+            bytes,
+            pos: 0,
+            last: true,
+        }
+    }
+}
+
+/// Yields the decoded runs of one chunk passed to `Utf8Decoder`.
+///
+/// This can't implement `std::iter::Iterator`: some items borrow from the
+/// `Utf8Decoder`'s internal buffer rather than from the input slice, so
+/// their lifetime is tied to each call to `next` rather than fixed up
+/// front. Drive it with a `while let Some(item) = chunks.next()` loop
+/// instead of a `for` loop.
+pub struct Utf8Chunks<'d, 'b> {
+    decoder: &'d mut Utf8Decoder,
+    bytes: &'b [u8],
+    pos: usize,
+    last: bool,
+}
+
+impl<'d, 'b> Utf8Chunks<'d, 'b> {
+    /// Return the next valid `&str` run, or the byte span of the next
+    /// ill-formed subsequence. Returns `None` once `bytes` is exhausted
+    /// (for `next_chunk`, a trailing incomplete sequence is buffered rather
+    /// than reported, so it produces `None` too).
+    pub fn next(&mut self) -> Option<Result<&str, &[u8]>> {
+        if self.decoder.incomplete_len > 0 {
+            return self.complete_incomplete();
+        }
+        if self.pos >= self.bytes.len() {
+            return None;
+        }
+
+        let start = self.pos;
+        let mut state = State::VALID;
+        let mut seq_start = self.pos;
+        while self.pos < self.bytes.len() {
+            let b = self.bytes[self.pos];
+            let next_state = transition(state, b);
+            match next_state {
+                State::INVALID => {
+                    if start < seq_start {
+                        // Emit the valid run before the ill-formed sequence;
+                        // leave `pos` at `seq_start` so the next call reports
+                        // the ill-formed sequence itself.
+                        self.pos = seq_start;
+                        return Some(Ok(unsafe {
+                            std::str::from_utf8_unchecked(&self.bytes[start..seq_start])
+                        }));
+                    }
+                    if self.pos == seq_start {
+                        // Rejected directly from `State::VALID`: `b` can't
+                        // legally begin a sequence either, so it is itself
+                        // the ill-formed subsequence.
+                        self.pos += 1;
+                        return Some(Err(&self.bytes[seq_start..self.pos]));
+                    }
+                    // Rejected mid-sequence: per the maximal-subparts rule,
+                    // `b` doesn't belong to the ill-formed subsequence.
+                    // Leave it unconsumed so the next call reprocesses it
+                    // as the possible start of a new sequence.
+                    return Some(Err(&self.bytes[seq_start..self.pos]));
+                }
+                State::VALID => {
+                    self.pos += 1;
+                    seq_start = self.pos;
+                    state = State::VALID;
+                }
+                _ => {
+                    self.pos += 1;
+                    state = next_state;
+                }
+            }
+        }
+
+        if let State::VALID = state {
+            if self.pos > start {
+                return Some(Ok(unsafe {
+                    std::str::from_utf8_unchecked(&self.bytes[start..self.pos])
+                }));
+            }
+            return None;
+        }
+
+        // A sequence is still in progress at the end of this chunk: buffer
+        // it and let `complete_incomplete` decide its fate.
+        let tail = &self.bytes[seq_start..self.pos];
+        self.decoder.incomplete[..tail.len()].copy_from_slice(tail);
+        self.decoder.incomplete_len = tail.len();
+        if start < seq_start {
+            return Some(Ok(unsafe {
+                std::str::from_utf8_unchecked(&self.bytes[start..seq_start])
+            }));
+        }
+        self.complete_incomplete()
+    }
+
+    /// Try to extend the sequence buffered in `decoder.incomplete` with
+    /// bytes from this chunk.
+    fn complete_incomplete(&mut self) -> Option<Result<&str, &[u8]>> {
+        // The buffered bytes were already validated as a well-formed
+        // prefix, so replaying them from `State::VALID` reconstructs the
+        // DFA state they left off in.
+        let mut state = State::VALID;
+        for &b in &self.decoder.incomplete[..self.decoder.incomplete_len] {
+            state = transition(state, b);
+        }
+
+        while self.pos < self.bytes.len() {
+            let b = self.bytes[self.pos];
+            let next_state = transition(state, b);
+            match next_state {
+                State::INVALID => {
+                    let len = self.decoder.incomplete_len;
+                    self.decoder.incomplete_len = 0;
+                    return Some(Err(&self.decoder.incomplete[..len]));
+                }
+                State::VALID => {
+                    self.decoder.incomplete[self.decoder.incomplete_len] = b;
+                    self.decoder.incomplete_len += 1;
+                    self.pos += 1;
+                    let len = self.decoder.incomplete_len;
+                    self.decoder.incomplete_len = 0;
+                    return Some(Ok(unsafe {
+                        std::str::from_utf8_unchecked(&self.decoder.incomplete[..len])
+                    }));
+                }
+                _ => {
+                    self.decoder.incomplete[self.decoder.incomplete_len] = b;
+                    self.decoder.incomplete_len += 1;
+                    self.pos += 1;
+                    state = next_state;
+                }
+            }
+        }
+
+        if self.last {
+            let len = self.decoder.incomplete_len;
+            self.decoder.incomplete_len = 0;
+            Some(Err(&self.decoder.incomplete[..len]))
+        } else {
+            None
         }
     }
 }
@@ -162,6 +578,51 @@ mod tests {
         assert!(v.validate(&b).and_then(|_| v.validate_end()).is_err());
     }
 
+    #[test]
+    fn it_reports_valid_prefix_len_and_invalid_len() {
+        let mut v = Utf8Validator::new();
+        let b = vec![b'a', b'b', 0b11111111];
+        let err = v.validate(&b).unwrap_err();
+        assert_eq!(
+            err,
+            DecodeError::Invalid {
+                valid_prefix_len: 2,
+                invalid_len: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn it_excludes_a_legal_restart_byte_from_invalid_len() {
+        // 0xE0 starts a 3-byte sequence, but 0x41 ('A') can't continue it
+        // and is itself a perfectly good ASCII byte, so the ill-formed
+        // subsequence is just the lead byte, not the lead byte plus 'A'.
+        let mut v = Utf8Validator::new();
+        let b = vec![b'a', 0xe0, 0x41];
+        let err = v.validate(&b).unwrap_err();
+        assert_eq!(
+            err,
+            DecodeError::Invalid {
+                valid_prefix_len: 1,
+                invalid_len: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn it_reports_incomplete_at_end_of_stream() {
+        let mut v = Utf8Validator::new();
+        let b = vec![b'a', 0b11110000, 0b10010000];
+        v.validate(&b).expect("a valid prefix followed by a partial sequence");
+        let err = v.validate_end().unwrap_err();
+        assert_eq!(
+            err,
+            DecodeError::Incomplete {
+                valid_prefix_len: 1,
+            }
+        );
+    }
+
     #[test]
     fn it_rejects_too_large_codepoints() {
         let mut v = Utf8Validator::new();
@@ -189,5 +650,247 @@ mod tests {
             valid &= v.validate_end().is_ok();
             assert_eq!(valid, expect_valid);
         }
+
+        #[test]
+        fn it_matches_stdlib_error_offsets(b: Vec<u8>) {
+            // Only `str::from_utf8`'s ill-formed-subsequence case maps onto
+            // `DecodeError::Invalid`; an incomplete trailing sequence
+            // (`error_len() == None`) maps onto `DecodeError::Incomplete`
+            // instead and isn't covered here.
+            if let Err(std_err) = str::from_utf8(&b) {
+                if let Some(error_len) = std_err.error_len() {
+                    let mut v = Utf8Validator::new();
+                    assert_eq!(
+                        v.validate(&b),
+                        Err(DecodeError::Invalid {
+                            valid_prefix_len: std_err.valid_up_to(),
+                            invalid_len: error_len,
+                        })
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn it_lossy_decodes_valid_text() {
+        let mut d = Utf8LossyDecoder::new();
+        let mut out = String::new();
+        d.decode("abc丂丄丅丆丏".as_bytes(), &mut out);
+        d.finish(&mut out);
+        assert_eq!(out, "abc丂丄丅丆丏");
+    }
+
+    #[test]
+    fn it_lossy_decodes_invalid_start_byte() {
+        let mut d = Utf8LossyDecoder::new();
+        let mut out = String::new();
+        d.decode(&[0b11111111], &mut out);
+        d.finish(&mut out);
+        assert_eq!(out, "\u{fffd}");
+    }
+
+    #[test]
+    fn it_lossy_decodes_invalid_byte_then_resumes_with_ascii() {
+        let mut d = Utf8LossyDecoder::new();
+        let mut out = String::new();
+        d.decode(&[0xe0, 0x7f], &mut out);
+        d.finish(&mut out);
+        assert_eq!(out, "\u{fffd}\u{7f}");
+    }
+
+    #[test]
+    fn it_lossy_decodes_incomplete_sequence_at_end() {
+        let mut d = Utf8LossyDecoder::new();
+        let mut out = String::new();
+        d.decode(&[0b11110000, 0b10010000], &mut out);
+        d.finish(&mut out);
+        assert_eq!(out, "\u{fffd}");
+    }
+
+    #[test]
+    fn it_lossy_decodes_multi_byte_char_split_between_reads() {
+        let mut d = Utf8LossyDecoder::new();
+        let mut out = String::new();
+        let s = "¢ह€한";
+        for b in s.as_bytes() {
+            d.decode(&[*b], &mut out);
+        }
+        d.finish(&mut out);
+        assert_eq!(out, s);
+    }
+
+    proptest! {
+        // `Utf8Validator` and `Utf8LossyDecoder` share the same DFA, so
+        // whatever the validator accepts as fully valid must round-trip
+        // through the lossy decoder unchanged.
+        #[test]
+        fn it_lossy_decodes_validator_accepted_input_unchanged(b: Vec<u8>) {
+            let mut v = Utf8Validator::new();
+            let is_valid = v.validate(&b).and_then(|_| v.validate_end()).is_ok();
+
+            let mut d = Utf8LossyDecoder::new();
+            let mut out = String::new();
+            d.decode(&b, &mut out);
+            d.finish(&mut out);
+
+            if is_valid {
+                assert_eq!(out.as_bytes(), &b[..]);
+            }
+        }
+
+        #[test]
+        fn it_lossy_decodes_validator_accepted_input_unchanged_with_splits(b: Vec<u8>) {
+            let mut v = Utf8Validator::new();
+            let is_valid = v.validate(&b).and_then(|_| v.validate_end()).is_ok();
+
+            let mut d = Utf8LossyDecoder::new();
+            let mut out = String::new();
+            for byte in b.iter() {
+                d.decode(&[*byte], &mut out);
+            }
+            d.finish(&mut out);
+
+            if is_valid {
+                assert_eq!(out.as_bytes(), &b[..]);
+            }
+        }
+    }
+
+    /// Feed `chunks` through a fresh `Utf8Decoder` and collect every decoded
+    /// run (`Ok` text or `Err` byte span) into one owned `Vec` for easy
+    /// asserting (items borrow from the decoder, so they can't outlive it).
+    fn decode_chunks(chunks: &[&[u8]]) -> Vec<Result<String, Vec<u8>>> {
+        let mut decoder = Utf8Decoder::new();
+        let mut out = Vec::new();
+        for (i, chunk) in chunks.iter().enumerate() {
+            let mut c = if i + 1 == chunks.len() {
+                decoder.last_chunk(chunk)
+            } else {
+                decoder.next_chunk(chunk)
+            };
+            while let Some(item) = c.next() {
+                out.push(item.map(String::from).map_err(Vec::from));
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn it_decodes_valid_text_in_one_run() {
+        let out = decode_chunks(&["abc丂丄丅丆丏".as_bytes()]);
+        assert_eq!(out, vec![Ok("abc丂丄丅丆丏".to_string())]);
+    }
+
+    #[test]
+    fn it_decodes_invalid_start_byte() {
+        let out = decode_chunks(&[&[0b11111111]]);
+        assert_eq!(out, vec![Err(vec![0b11111111])]);
+    }
+
+    #[test]
+    fn it_decodes_valid_run_then_invalid_byte() {
+        let out = decode_chunks(&[b"ab", &[0b11111111], b"cd"]);
+        assert_eq!(
+            out,
+            vec![
+                Ok("ab".to_string()),
+                Err(vec![0b11111111]),
+                Ok("cd".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_decodes_a_sequence_split_across_chunks() {
+        let s = "¢ह€한";
+        let chunks: Vec<&[u8]> = s.as_bytes().iter().map(std::slice::from_ref).collect();
+        let out = decode_chunks(&chunks);
+        let joined: String = out.into_iter().map(|r| r.expect("valid")).collect();
+        assert_eq!(joined, s);
+    }
+
+    #[test]
+    fn it_reports_a_sequence_left_incomplete_by_last_chunk() {
+        let out = decode_chunks(&[&[0b11110000, 0b10010000]]);
+        assert_eq!(out, vec![Err(vec![0b11110000, 0b10010000])]);
+    }
+
+    #[test]
+    fn it_does_not_consume_a_legal_restart_byte_after_mid_sequence_rejection() {
+        // 0xE0 starts a 3-byte sequence, but 0x41 ('A') can't continue it
+        // and is itself a perfectly good ASCII byte, so it must survive as
+        // valid text rather than being swallowed into the error span.
+        let out = decode_chunks(&[&[0xe0, 0x41]]);
+        assert_eq!(out, vec![Err(vec![0xe0]), Ok("A".to_string())]);
+    }
+
+    #[test]
+    fn it_does_not_consume_a_legal_restart_lead_byte_after_mid_sequence_rejection() {
+        // Likewise when the restart byte is itself a fresh lead byte.
+        let out = decode_chunks(&[&[0xe0, 0xc2, 0xa2]]);
+        assert_eq!(out, vec![Err(vec![0xe0]), Ok("\u{a2}".to_string())]);
+    }
+
+    /// Recover the valid `&str` runs `std::str::from_utf8` would see if it
+    /// were repeatedly asked to re-scan past each ill-formed subsequence it
+    /// reports, the same way `String::from_utf8_lossy` does. A trailing
+    /// incomplete sequence (`error_len() == None`) never becomes a valid
+    /// run, matching `Utf8Decoder::last_chunk` reporting it as an error too.
+    fn std_valid_runs(mut bytes: &[u8]) -> Vec<String> {
+        let mut runs = Vec::new();
+        loop {
+            match str::from_utf8(bytes) {
+                Ok(s) => {
+                    if !s.is_empty() {
+                        runs.push(s.to_string());
+                    }
+                    break;
+                }
+                Err(e) => {
+                    let valid_up_to = e.valid_up_to();
+                    if valid_up_to > 0 {
+                        runs.push(str::from_utf8(&bytes[..valid_up_to]).unwrap().to_string());
+                    }
+                    match e.error_len() {
+                        Some(len) => bytes = &bytes[valid_up_to + len..],
+                        None => break,
+                    }
+                }
+            }
+        }
+        runs
+    }
+
+    proptest! {
+        #[test]
+        fn it_decoder_matches_stdlib_str_behavior_no_splits(b: Vec<u8>) {
+            let out = decode_chunks(&[&b]);
+            let all_valid = out.iter().all(Result::is_ok);
+            assert_eq!(all_valid, str::from_utf8(&b).is_ok());
+            if all_valid {
+                let joined: String = out.into_iter().map(|r| r.unwrap()).collect();
+                assert_eq!(joined, str::from_utf8(&b).unwrap());
+            }
+        }
+
+        #[test]
+        fn it_decoder_matches_stdlib_str_behavior_with_splits(b: Vec<u8>) {
+            let chunks: Vec<&[u8]> = b.iter().map(std::slice::from_ref).collect();
+            let out = decode_chunks(&chunks);
+            let all_valid = out.iter().all(Result::is_ok);
+            assert_eq!(all_valid, str::from_utf8(&b).is_ok());
+            if all_valid {
+                let joined: String = out.into_iter().map(|r| r.unwrap()).collect();
+                assert_eq!(joined, str::from_utf8(&b).unwrap());
+            }
+        }
+
+        #[test]
+        fn it_decoder_ok_runs_match_stdlib_recovered_text(b: Vec<u8>) {
+            let out = decode_chunks(&[&b]);
+            let decoder_runs: Vec<String> = out.into_iter().filter_map(Result::ok).collect();
+            assert_eq!(decoder_runs, std_valid_runs(&b));
+        }
     }
 }